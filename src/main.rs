@@ -1,7 +1,299 @@
 use rand::Rng;
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::time::Instant;
 
+// --- 0. BOOK-SIDE STORAGE (crit-bit slab) ---
+//
+// Each side of the book used to be a BTreeMap<price, VecDeque<Order>> with a
+// linear scan over the VecDeque to cancel. At order counts in the millions
+// that scan is the bottleneck, so bids/asks are now a crit-bit tree over a
+// packed (price, sequence) key, backed by a flat arena (`Slab`) with a free
+// list so a cancelled order's slot is reused instead of the allocator
+// churning on every call.
+//
+// Keys are packed as `(price_component << 64) | sequence`, where `sequence`
+// is a counter assigned at parking time (time priority). Ascending key order
+// is always "best first": asks pack the real price so the lowest price
+// sorts first; bids pack its bitwise complement so the highest price sorts
+// first. Either way, a tied price breaks on the lower sequence (earlier
+// order) first, which is why `Slab::best`/`iter_best_first` only ever need
+// to look at the smallest key.
+
+#[derive(Debug)]
+enum SlabNode {
+    Leaf {
+        key: u128,
+        order: Order,
+        parent: Option<u32>,
+    },
+    Inner {
+        // Bit index counted from the MSB (0..128) of the first bit at which
+        // the keys in this subtree differ.
+        critbit: u32,
+        left: u32,
+        right: u32,
+        parent: Option<u32>,
+    },
+}
+
+struct Slab {
+    nodes: Vec<Option<SlabNode>>,
+    free: Vec<u32>,
+    root: Option<u32>,
+}
+
+impl Slab {
+    fn new() -> Slab {
+        Slab {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+        }
+    }
+
+    fn bit_at(key: u128, bit_from_msb: u32) -> bool {
+        (key >> (127 - bit_from_msb)) & 1 == 1
+    }
+
+    fn alloc(&mut self, node: SlabNode) -> u32 {
+        if let Some(idx) = self.free.pop() {
+            self.nodes[idx as usize] = Some(node);
+            idx
+        } else {
+            self.nodes.push(Some(node));
+            (self.nodes.len() - 1) as u32
+        }
+    }
+
+    fn parent_of(&self, idx: u32) -> Option<u32> {
+        match self.nodes[idx as usize].as_ref().unwrap() {
+            SlabNode::Leaf { parent, .. } => *parent,
+            SlabNode::Inner { parent, .. } => *parent,
+        }
+    }
+
+    fn set_parent(&mut self, idx: u32, new_parent: Option<u32>) {
+        match self.nodes[idx as usize].as_mut().unwrap() {
+            SlabNode::Leaf { parent, .. } => *parent = new_parent,
+            SlabNode::Inner { parent, .. } => *parent = new_parent,
+        }
+    }
+
+    /// Inserts `order` under `key` (must be unique in this tree) and returns
+    /// a handle that `get`/`get_mut`/`remove` can use directly, in O(1).
+    fn insert(&mut self, key: u128, order: Order) -> u32 {
+        let new_idx = self.alloc(SlabNode::Leaf {
+            key,
+            order,
+            parent: None,
+        });
+
+        let root = match self.root {
+            None => {
+                self.root = Some(new_idx);
+                return new_idx;
+            }
+            Some(r) => r,
+        };
+
+        // Descend as if `key` were already present, to find the leaf it's
+        // "closest" to.
+        let mut probe = root;
+        loop {
+            match self.nodes[probe as usize].as_ref().unwrap() {
+                SlabNode::Leaf { .. } => break,
+                SlabNode::Inner {
+                    critbit,
+                    left,
+                    right,
+                    ..
+                } => {
+                    probe = if Self::bit_at(key, *critbit) {
+                        *right
+                    } else {
+                        *left
+                    };
+                }
+            }
+        }
+        let closest_key = match self.nodes[probe as usize].as_ref().unwrap() {
+            SlabNode::Leaf { key, .. } => *key,
+            _ => unreachable!(),
+        };
+        let diff_bit = (key ^ closest_key).leading_zeros();
+
+        // Walk from the root again to find where to splice the new node in:
+        // the first point where an inner node's critbit reaches `diff_bit`,
+        // or a leaf.
+        let mut parent_idx: Option<u32> = None;
+        let mut cur = root;
+        let mut came_from_right = false;
+        loop {
+            match self.nodes[cur as usize].as_ref().unwrap() {
+                SlabNode::Leaf { .. } => break,
+                SlabNode::Inner {
+                    critbit,
+                    left,
+                    right,
+                    ..
+                } => {
+                    if *critbit >= diff_bit {
+                        break;
+                    }
+                    parent_idx = Some(cur);
+                    came_from_right = Self::bit_at(key, *critbit);
+                    cur = if came_from_right { *right } else { *left };
+                }
+            }
+        }
+
+        let new_key_bit = Self::bit_at(key, diff_bit);
+        let (left_child, right_child) = if new_key_bit {
+            (cur, new_idx)
+        } else {
+            (new_idx, cur)
+        };
+        let new_inner = self.alloc(SlabNode::Inner {
+            critbit: diff_bit,
+            left: left_child,
+            right: right_child,
+            parent: parent_idx,
+        });
+        self.set_parent(left_child, Some(new_inner));
+        self.set_parent(right_child, Some(new_inner));
+
+        match parent_idx {
+            None => self.root = Some(new_inner),
+            Some(p) => {
+                if let SlabNode::Inner { left, right, .. } = self.nodes[p as usize].as_mut().unwrap() {
+                    if came_from_right {
+                        *right = new_inner;
+                    } else {
+                        *left = new_inner;
+                    }
+                }
+            }
+        }
+
+        new_idx
+    }
+
+    /// Removes the leaf at `handle`, unlinking it and its parent and
+    /// promoting the sibling up to the grandparent. O(1) given the handle
+    /// and the parent pointers stored on every node.
+    fn remove(&mut self, handle: u32) -> Order {
+        let parent_idx = self.parent_of(handle);
+
+        match parent_idx {
+            None => self.root = None,
+            Some(p) => {
+                let grandparent = self.parent_of(p);
+                let sibling = match self.nodes[p as usize].as_ref().unwrap() {
+                    SlabNode::Inner { left, right, .. } => {
+                        if *left == handle {
+                            *right
+                        } else {
+                            *left
+                        }
+                    }
+                    _ => unreachable!(),
+                };
+                self.set_parent(sibling, grandparent);
+                match grandparent {
+                    None => self.root = Some(sibling),
+                    Some(g) => {
+                        if let SlabNode::Inner { left, right, .. } =
+                            self.nodes[g as usize].as_mut().unwrap()
+                        {
+                            if *left == p {
+                                *left = sibling;
+                            } else {
+                                *right = sibling;
+                            }
+                        }
+                    }
+                }
+                self.nodes[p as usize] = None;
+                self.free.push(p);
+            }
+        }
+
+        let leaf = self.nodes[handle as usize].take().unwrap();
+        self.free.push(handle);
+
+        match leaf {
+            SlabNode::Leaf { order, .. } => order,
+            _ => unreachable!(),
+        }
+    }
+
+    fn get(&self, handle: u32) -> &Order {
+        match self.nodes[handle as usize].as_ref().unwrap() {
+            SlabNode::Leaf { order, .. } => order,
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_mut(&mut self, handle: u32) -> &mut Order {
+        match self.nodes[handle as usize].as_mut().unwrap() {
+            SlabNode::Leaf { order, .. } => order,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Handle of the best (lowest-key) resting order, if any.
+    fn best(&self) -> Option<u32> {
+        let mut idx = self.root?;
+        loop {
+            match self.nodes[idx as usize].as_ref().unwrap() {
+                SlabNode::Leaf { .. } => return Some(idx),
+                SlabNode::Inner { left, .. } => idx = *left,
+            }
+        }
+    }
+
+    /// Walks every resting order best-first. Every leaf in a node's left
+    /// subtree sorts below every leaf in its right subtree (that's what
+    /// "critbit" means), so pushing right-then-left and popping is enough
+    /// to get ascending key order without an explicit in-order traversal.
+    fn iter_best_first(&self) -> SlabIter<'_> {
+        SlabIter {
+            slab: self,
+            stack: self.root.into_iter().collect(),
+        }
+    }
+}
+
+struct SlabIter<'a> {
+    slab: &'a Slab,
+    stack: Vec<u32>,
+}
+
+impl<'a> Iterator for SlabIter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<&'a Order> {
+        loop {
+            let idx = self.stack.pop()?;
+            match self.slab.nodes[idx as usize].as_ref().unwrap() {
+                SlabNode::Leaf { order, .. } => return Some(order),
+                SlabNode::Inner { left, right, .. } => {
+                    self.stack.push(*right);
+                    self.stack.push(*left);
+                }
+            }
+        }
+    }
+}
+
+fn pack_ask_key(price: u64, sequence: u64) -> u128 {
+    ((price as u128) << 64) | sequence as u128
+}
+
+fn pack_bid_key(price: u64, sequence: u64) -> u128 {
+    (((u64::MAX - price) as u128) << 64) | sequence as u128
+}
+
 // --- 1. DATA STRUCTURES ---
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -10,155 +302,1032 @@ enum OrderType {
     Sell,
 }
 
+// Limit orders rest on the book at `price` if unfilled; market orders sweep
+// the book at whatever price is available and are discarded if not fully
+// filled rather than being parked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrderKind {
+    Limit,
+    Market,
+}
+
+// How to resolve a match between two orders sharing the same owner. Selected
+// per incoming (taker) order; the comparison is always against the taker's
+// own owner id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SelfTradeBehavior {
+    CancelProvide,
+    DecrementTake,
+    AbortTransaction,
+}
+
 #[derive(Debug, Clone)]
 struct Order {
     id: u64,
     order_type: OrderType,
-   
+    kind: OrderKind,
+    owner: u64,
+    self_trade_behavior: SelfTradeBehavior,
+    // Good-till-date orders carry a Unix timestamp after which they're dead
+    // and must be evicted instead of matched; `None` means good-till-cancel.
+    expiry_ts: Option<u64>,
+    // `Some(offset)` makes this an oracle-pegged order: its resting price is
+    // `oracle_price + offset` (clamped at zero), re-resolved at match time,
+    // instead of the fixed `price` below. `None` is a normal fixed-price order.
+    peg_offset: Option<i64>,
+
     price: u64,
     quantity: u32,
 }
 
+// Upper bound on how many expired resting orders a single `add_order` call
+// will evict, counted across every level it walks. Keeps tail latency bounded even when a
+// level is full of stale GTD orders; anything past the limit is left for a
+// later call to clean up.
+const DROP_EXPIRED_ORDER_LIMIT: usize = 10;
+
+// A completed trade between a resting (maker) order and an incoming (taker) order.
+// Trades always execute at the maker's price, never the taker's.
+#[derive(Debug, Clone, PartialEq)]
+struct FillEvent {
+    taker_id: u64,
+    maker_id: u64,
+    price: u64,
+    quantity: u32,
+}
+
+// Rejections raised by `add_order` before an order ever touches the book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrderError {
+    InvalidTick,
+    InvalidLot,
+    BelowMinimum,
+    SelfTrade,
+}
+
+// Where a resting order was parked, so cancel/amend/eviction can jump
+// straight to the right structure instead of scanning every level on both
+// sides. Fixed-price handles are slab-specific, so bid/ask and pegged
+// bid/ask each need their own variant -- a `u32` handle from one slab isn't
+// meaningful in the other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrderLocation {
+    FixedBid(u32),
+    FixedAsk(u32),
+    PeggedBid(i64),
+    PeggedAsk(i64),
+}
+
+// Which side of the book a market-data query is reading. Distinct from
+// `OrderType`, which is the direction of an incoming order rather than a
+// side of the resting book.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Bid,
+    Ask,
+}
+
 struct OrderBook {
     // Bids (Buyers): We want the HIGHEST price first.
     // Asks (Sellers): We want the LOWEST price first.
-    // BTreeMap sorts Low -> High automatically.
-    bids: BTreeMap<u64, VecDeque<Order>>,
-    asks: BTreeMap<u64, VecDeque<Order>>,
+    // Each side is a crit-bit slab (see the module comment above); ascending
+    // key order is always best-first for both, via the key packing.
+    bids: Slab,
+    asks: Slab,
+    next_sequence: u64,
+
+    // Oracle-pegged orders, keyed by signed offset from the oracle price
+    // rather than an absolute price. Offset order and effective-price order
+    // always agree (effective price is a monotonic function of the offset
+    // for any fixed oracle price), so `set_oracle_price` never has to
+    // reshuffle these trees — only the read side re-resolves prices.
+    pegged_bids: BTreeMap<i64, VecDeque<Order>>,
+    pegged_asks: BTreeMap<i64, VecDeque<Order>>,
+    oracle_price: u64,
+
+    order_locations: HashMap<u64, OrderLocation>,
+
+    // Grid the book enforces on every incoming order, to keep dust and
+    // off-grid prices from accumulating.
+    tick_size: u64,
+    lot_size: u32,
+    min_size: u32,
 }
 
 impl OrderBook {
-    fn new() -> OrderBook {
+    fn new(tick_size: u64, lot_size: u32, min_size: u32) -> OrderBook {
         OrderBook {
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
+            bids: Slab::new(),
+            asks: Slab::new(),
+            next_sequence: 0,
+            pegged_bids: BTreeMap::new(),
+            pegged_asks: BTreeMap::new(),
+            oracle_price: 0,
+            order_locations: HashMap::new(),
+            tick_size,
+            lot_size,
+            min_size,
         }
     }
 
-    fn add_order(&mut self, mut order: Order) {
-        match order.order_type {
-            OrderType::Buy => self.match_buy_order(&mut order),
-            OrderType::Sell => self.match_sell_order(&mut order),
+    /// Updates the reference price pegged orders are priced from. O(1): see
+    /// the comment on `pegged_bids`/`pegged_asks` for why no rebuild is needed.
+    fn set_oracle_price(&mut self, price: u64) {
+        self.oracle_price = price;
+    }
+
+    /// Resolves a peg offset against the current oracle price, clamped to
+    /// `[0, u64::MAX]` (prices can't go negative, and an extreme oracle
+    /// price plus an extreme offset must saturate instead of wrapping).
+    /// Widened to i128 because `oracle_price` and `offset` are each within a
+    /// factor of 2 of i64's range, so their sum can overflow i64 in either
+    /// direction before the clamp ever gets a chance to run.
+    fn effective_pegged_price(&self, offset: i64) -> u64 {
+        let price = self.oracle_price as i128 + offset as i128;
+        price.clamp(0, u64::MAX as i128) as u64
+    }
+
+    fn add_order(&mut self, mut order: Order, now_ts: u64) -> Result<Vec<FillEvent>, OrderError> {
+        // Limit orders must sit on the price grid; market orders and pegged
+        // orders have no trader-specified absolute price to validate.
+        //
+        // `is_multiple_of` (rather than `% != 0`) is deliberate: a book
+        // misconfigured with `tick_size == 0` or `lot_size == 0` now rejects
+        // every non-zero order with InvalidTick/InvalidLot instead of
+        // panicking on division by zero. This used to be a silent
+        // side effect of an unrelated clippy cleanup; it's called out here
+        // and covered by a test because it's a real, intentional behavior
+        // change, not just a lint fix.
+        if order.kind == OrderKind::Limit
+            && order.peg_offset.is_none()
+            && !order.price.is_multiple_of(self.tick_size)
+        {
+            return Err(OrderError::InvalidTick);
         }
+        if !order.quantity.is_multiple_of(self.lot_size) {
+            return Err(OrderError::InvalidLot);
+        }
+        if order.quantity < self.min_size {
+            return Err(OrderError::BelowMinimum);
+        }
+
+        // Resolve a pegged taker's effective price against the oracle now,
+        // at entry -- the oracle can't change mid-call, so this one snapshot
+        // is exactly "at match time" for the whole of this add_order.
+        if let Some(offset) = order.peg_offset {
+            order.price = self.effective_pegged_price(offset);
+        }
+
+        // Market orders don't carry a meaningful price of their own, so we
+        // give them an implicit limit price that crosses any resting order
+        // on the opposite side. This lets them flow through the same
+        // price-crossing checks the matching loop already does for limits.
+        if order.kind == OrderKind::Market {
+            order.price = match order.order_type {
+                OrderType::Buy => u64::MAX,
+                OrderType::Sell => 0,
+            };
+        }
+
+        // AbortTransaction must leave the book untouched if it fires, so we
+        // have to know *before* matching starts whether a self-trade would
+        // occur anywhere along the walk, rather than bailing out partway
+        // through (by which point earlier, legitimate fills would already
+        // have happened).
+        if order.self_trade_behavior == SelfTradeBehavior::AbortTransaction {
+            let would_self_trade = match order.order_type {
+                OrderType::Buy => self.would_self_trade_buy(&order, now_ts),
+                OrderType::Sell => self.would_self_trade_sell(&order, now_ts),
+            };
+            if would_self_trade {
+                return Err(OrderError::SelfTrade);
+            }
+        }
+
+        Ok(match order.order_type {
+            OrderType::Buy => self.match_buy_order(&mut order, now_ts),
+            OrderType::Sell => self.match_sell_order(&mut order, now_ts),
+        })
     }
 
-    // --- 2. MATCHING LOGIC (The Engine) ---
+    // Read-only walk mirroring `match_buy_order`'s consumption order, to
+    // decide whether an AbortTransaction order would ever reach one of its
+    // own resting orders before it stops matching. Expired makers are dead
+    // weight either way, so they're skipped here exactly as they would be
+    // evicted during real matching -- bounded by the same
+    // `DROP_EXPIRED_ORDER_LIMIT`, so a level stacked with stale GTD orders
+    // can't turn this preflight into an unbounded O(book-size) scan. Once
+    // the limit is hit, real matching would stop right there without ever
+    // reaching further resting orders, so this walk reports "no self-trade"
+    // rather than keep looking past the point real matching would give up.
+    fn would_self_trade_buy(&self, order: &Order, now_ts: u64) -> bool {
+        let mut remaining = order.quantity;
+        let mut expired_dropped = 0;
+        let mut fixed_iter = self.asks.iter_best_first().peekable();
+        let mut pegged_iter = self.pegged_asks.iter().peekable();
 
-    fn match_buy_order(&mut self, order: &mut Order) {
-      
-        while order.quantity > 0 {
-            // 1. PEEK: Look at the cheapest seller (First key in BTreeMap)
-            
-            let best_ask_price = if let Some(&price) = self.asks.keys().next() {
-                price
-            } else {
+        loop {
+            if remaining == 0 {
                 break;
+            }
+
+            let fixed_price = fixed_iter.peek().map(|o| o.price);
+            let pegged_price = pegged_iter
+                .peek()
+                .map(|&(&offset, _)| self.effective_pegged_price(offset));
+
+            let take_fixed = match (fixed_price, pegged_price) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(fp), Some(pp)) => fp <= pp,
             };
 
-            // 2. CHECK: Is the seller too expensive?
-            if best_ask_price > order.price {
+            if take_fixed {
+                let resting = fixed_iter.next().unwrap();
+                if resting.price > order.price {
+                    break;
+                }
+                if resting.expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    expired_dropped += 1;
+                    continue;
+                }
+                if resting.owner == order.owner {
+                    return true;
+                }
+                remaining -= std::cmp::min(remaining, resting.quantity);
+            } else {
+                let (&offset, level) = pegged_iter.next().unwrap();
+                let price = self.effective_pegged_price(offset);
+                if price > order.price {
+                    break;
+                }
+                for resting in level.iter() {
+                    if remaining == 0 {
+                        break;
+                    }
+                    if resting.expiry_ts.is_some_and(|e| e <= now_ts) {
+                        if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                            return false;
+                        }
+                        expired_dropped += 1;
+                        continue;
+                    }
+                    if resting.owner == order.owner {
+                        return true;
+                    }
+                    remaining -= std::cmp::min(remaining, resting.quantity);
+                }
+            }
+        }
+        false
+    }
+
+    fn would_self_trade_sell(&self, order: &Order, now_ts: u64) -> bool {
+        let mut remaining = order.quantity;
+        let mut expired_dropped = 0;
+        let mut fixed_iter = self.bids.iter_best_first().peekable();
+        let mut pegged_iter = self.pegged_bids.iter().rev().peekable();
+
+        loop {
+            if remaining == 0 {
                 break;
             }
 
-            // 3. MATCH: Get the queue of sellers at this price
-            let orders_at_level = self.asks.get_mut(&best_ask_price).unwrap();
+            let fixed_price = fixed_iter.peek().map(|o| o.price);
+            let pegged_price = pegged_iter
+                .peek()
+                .map(|&(&offset, _)| self.effective_pegged_price(offset));
+
+            let take_fixed = match (fixed_price, pegged_price) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(fp), Some(pp)) => fp >= pp,
+            };
+
+            if take_fixed {
+                let resting = fixed_iter.next().unwrap();
+                if resting.price < order.price {
+                    break;
+                }
+                if resting.expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    expired_dropped += 1;
+                    continue;
+                }
+                if resting.owner == order.owner {
+                    return true;
+                }
+                remaining -= std::cmp::min(remaining, resting.quantity);
+            } else {
+                let (&offset, level) = pegged_iter.next().unwrap();
+                let price = self.effective_pegged_price(offset);
+                if price < order.price {
+                    break;
+                }
+                for resting in level.iter() {
+                    if remaining == 0 {
+                        break;
+                    }
+                    if resting.expiry_ts.is_some_and(|e| e <= now_ts) {
+                        if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                            return false;
+                        }
+                        expired_dropped += 1;
+                        continue;
+                    }
+                    if resting.owner == order.owner {
+                        return true;
+                    }
+                    remaining -= std::cmp::min(remaining, resting.quantity);
+                }
+            }
+        }
+        false
+    }
+
+    // --- 2. MATCHING LOGIC (The Engine) ---
 
-            // (FIFO - Time Priority)
-            while order.quantity > 0 && !orders_at_level.is_empty() {
-                let best_ask = orders_at_level.front_mut().unwrap();
+    fn match_buy_order(&mut self, order: &mut Order, now_ts: u64) -> Vec<FillEvent> {
+        let mut fills = Vec::new();
+        let mut expired_dropped = 0;
 
-                
-                let trade_qty = std::cmp::min(order.quantity, best_ask.quantity);
+        while order.quantity > 0 {
+            // 1. PEEK: cheapest seller, fixed-price slab and pegged tree
+            // interleaved by effective price (lowest wins for asks).
+            let fixed_best = self.asks.best().map(|h| self.asks.get(h).price);
+            let pegged_best = self.pegged_asks.keys().next().copied();
+            let pegged_best_price = pegged_best.map(|offset| self.effective_pegged_price(offset));
+
+            let use_pegged = match (fixed_best, pegged_best_price) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some(fp), Some(pp)) => pp < fp,
+            };
+
+            if use_pegged {
+                let offset = pegged_best.unwrap();
+                let best_ask_price = pegged_best_price.unwrap();
+
+                // 2. CHECK: Is the seller too expensive?
+                if best_ask_price > order.price {
+                    break;
+                }
+
+                // 3. MATCH: front of the resting queue at this peg offset
+                let level = self.pegged_asks.get_mut(&offset).unwrap();
+
+                if level.front().unwrap().expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    let expired_id = level.pop_front().unwrap().id;
+                    if level.is_empty() {
+                        self.pegged_asks.remove(&offset);
+                    }
+                    self.order_locations.remove(&expired_id);
+                    expired_dropped += 1;
+                    continue;
+                }
 
-                // Execute Trade 
+                if level.front().unwrap().owner == order.owner {
+                    match order.self_trade_behavior {
+                        SelfTradeBehavior::CancelProvide => {
+                            let cancelled_id = level.pop_front().unwrap().id;
+                            if level.is_empty() {
+                                self.pegged_asks.remove(&offset);
+                            }
+                            self.order_locations.remove(&cancelled_id);
+                            continue;
+                        }
+                        SelfTradeBehavior::DecrementTake => {
+                            let maker = level.front_mut().unwrap();
+                            let overlap = std::cmp::min(order.quantity, maker.quantity);
+                            order.quantity -= overlap;
+                            maker.quantity -= overlap;
+                            if maker.quantity == 0 {
+                                let id = level.pop_front().unwrap().id;
+                                if level.is_empty() {
+                                    self.pegged_asks.remove(&offset);
+                                }
+                                self.order_locations.remove(&id);
+                            }
+                            continue;
+                        }
+                        SelfTradeBehavior::AbortTransaction => break,
+                    }
+                }
+
+                let maker = level.front_mut().unwrap();
+                let trade_qty = std::cmp::min(order.quantity, maker.quantity);
                 order.quantity -= trade_qty;
-                best_ask.quantity -= trade_qty;
+                maker.quantity -= trade_qty;
 
-                // If seller is empty, remove them
-                if best_ask.quantity == 0 {
-                    orders_at_level.pop_front();
+                fills.push(FillEvent {
+                    taker_id: order.id,
+                    maker_id: maker.id,
+                    price: best_ask_price,
+                    quantity: trade_qty,
+                });
+
+                if maker.quantity == 0 {
+                    let filled_id = level.pop_front().unwrap().id;
+                    if level.is_empty() {
+                        self.pegged_asks.remove(&offset);
+                    }
+                    self.order_locations.remove(&filled_id);
                 }
-            }
+            } else {
+                let handle = self.asks.best().unwrap();
+                let best_ask_price = self.asks.get(handle).price;
+
+                // 2. CHECK: Is the seller too expensive?
+                if best_ask_price > order.price {
+                    break;
+                }
+
+                // 3. MATCH: the slab's best resting order
+                if self.asks.get(handle).expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    let expired = self.asks.remove(handle);
+                    self.order_locations.remove(&expired.id);
+                    expired_dropped += 1;
+                    continue;
+                }
+
+                if self.asks.get(handle).owner == order.owner {
+                    match order.self_trade_behavior {
+                        SelfTradeBehavior::CancelProvide => {
+                            let cancelled = self.asks.remove(handle);
+                            self.order_locations.remove(&cancelled.id);
+                            continue;
+                        }
+                        SelfTradeBehavior::DecrementTake => {
+                            let overlap =
+                                std::cmp::min(order.quantity, self.asks.get(handle).quantity);
+                            order.quantity -= overlap;
+                            self.asks.get_mut(handle).quantity -= overlap;
+                            if self.asks.get(handle).quantity == 0 {
+                                let drained = self.asks.remove(handle);
+                                self.order_locations.remove(&drained.id);
+                            }
+                            continue;
+                        }
+                        SelfTradeBehavior::AbortTransaction => break,
+                    }
+                }
+
+                let maker_id = self.asks.get(handle).id;
+                let trade_qty = std::cmp::min(order.quantity, self.asks.get(handle).quantity);
+                order.quantity -= trade_qty;
+                self.asks.get_mut(handle).quantity -= trade_qty;
 
-            // If no sellers left at this price, remove the price level
-            if orders_at_level.is_empty() {
-                self.asks.remove(&best_ask_price);
+                fills.push(FillEvent {
+                    taker_id: order.id,
+                    maker_id,
+                    price: best_ask_price,
+                    quantity: trade_qty,
+                });
+
+                if self.asks.get(handle).quantity == 0 {
+                    let filled = self.asks.remove(handle);
+                    self.order_locations.remove(&filled.id);
+                }
             }
         }
 
-        // 4. PARK: If order is not filled, put it in the book
-        if order.quantity > 0 {
-            self.bids
-                .entry(order.price)
-                .or_insert_with(VecDeque::new)
-                .push_back(order.clone());
+        // 4. PARK: If order is not filled, put it in the book (market orders
+        // are discarded instead of resting)
+        if order.quantity > 0 && order.kind == OrderKind::Limit {
+            match order.peg_offset {
+                Some(offset) => {
+                    self.order_locations
+                        .insert(order.id, OrderLocation::PeggedBid(offset));
+                    self.pegged_bids
+                        .entry(offset)
+                        .or_default()
+                        .push_back(order.clone());
+                }
+                None => {
+                    let sequence = self.next_sequence;
+                    self.next_sequence += 1;
+                    let key = pack_bid_key(order.price, sequence);
+                    let handle = self.bids.insert(key, order.clone());
+                    self.order_locations
+                        .insert(order.id, OrderLocation::FixedBid(handle));
+                }
+            }
         }
+
+        fills
     }
 
-    fn match_sell_order(&mut self, order: &mut Order) {
-       
+    fn match_sell_order(&mut self, order: &mut Order, now_ts: u64) -> Vec<FillEvent> {
+        let mut fills = Vec::new();
+        let mut expired_dropped = 0;
+
         while order.quantity > 0 {
-            // 1. PEEK: Look at highest bidder (Last key in BTreeMap)
-            // .keys().next_back() grabs the end of the map
-            let best_bid_price = if let Some(&price) = self.bids.keys().next_back() {
-                price
-            } else {
-                break;
+            // 1. PEEK: highest bidder, fixed-price slab and pegged tree
+            // interleaved by effective price (highest wins for bids).
+            let fixed_best = self.bids.best().map(|h| self.bids.get(h).price);
+            let pegged_best = self.pegged_bids.keys().next_back().copied();
+            let pegged_best_price = pegged_best.map(|offset| self.effective_pegged_price(offset));
+
+            let use_pegged = match (fixed_best, pegged_best_price) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some(fp), Some(pp)) => pp > fp,
             };
 
-            // 2. CHECK: Is the buyer offering enough?
-            if best_bid_price < order.price {
-                break;
-            }
+            if use_pegged {
+                let offset = pegged_best.unwrap();
+                let best_bid_price = pegged_best_price.unwrap();
 
-            // 3. MATCH
-            let orders_at_level = self.bids.get_mut(&best_bid_price).unwrap();
+                // 2. CHECK: Is the buyer offering enough?
+                if best_bid_price < order.price {
+                    break;
+                }
 
-            while order.quantity > 0 && !orders_at_level.is_empty() {
-                let best_bid = orders_at_level.front_mut().unwrap();
-                let trade_qty = std::cmp::min(order.quantity, best_bid.quantity);
+                // 3. MATCH
+                let level = self.pegged_bids.get_mut(&offset).unwrap();
 
+                if level.front().unwrap().expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    let expired_id = level.pop_front().unwrap().id;
+                    if level.is_empty() {
+                        self.pegged_bids.remove(&offset);
+                    }
+                    self.order_locations.remove(&expired_id);
+                    expired_dropped += 1;
+                    continue;
+                }
+
+                if level.front().unwrap().owner == order.owner {
+                    match order.self_trade_behavior {
+                        SelfTradeBehavior::CancelProvide => {
+                            let cancelled_id = level.pop_front().unwrap().id;
+                            if level.is_empty() {
+                                self.pegged_bids.remove(&offset);
+                            }
+                            self.order_locations.remove(&cancelled_id);
+                            continue;
+                        }
+                        SelfTradeBehavior::DecrementTake => {
+                            let maker = level.front_mut().unwrap();
+                            let overlap = std::cmp::min(order.quantity, maker.quantity);
+                            order.quantity -= overlap;
+                            maker.quantity -= overlap;
+                            if maker.quantity == 0 {
+                                let id = level.pop_front().unwrap().id;
+                                if level.is_empty() {
+                                    self.pegged_bids.remove(&offset);
+                                }
+                                self.order_locations.remove(&id);
+                            }
+                            continue;
+                        }
+                        SelfTradeBehavior::AbortTransaction => break,
+                    }
+                }
+
+                let maker = level.front_mut().unwrap();
+                let trade_qty = std::cmp::min(order.quantity, maker.quantity);
                 order.quantity -= trade_qty;
-                best_bid.quantity -= trade_qty;
+                maker.quantity -= trade_qty;
+
+                fills.push(FillEvent {
+                    taker_id: order.id,
+                    maker_id: maker.id,
+                    price: best_bid_price,
+                    quantity: trade_qty,
+                });
+
+                if maker.quantity == 0 {
+                    let filled_id = level.pop_front().unwrap().id;
+                    if level.is_empty() {
+                        self.pegged_bids.remove(&offset);
+                    }
+                    self.order_locations.remove(&filled_id);
+                }
+            } else {
+                let handle = self.bids.best().unwrap();
+                let best_bid_price = self.bids.get(handle).price;
+
+                // 2. CHECK: Is the buyer offering enough?
+                if best_bid_price < order.price {
+                    break;
+                }
+
+                // 3. MATCH: the slab's best resting order
+                if self.bids.get(handle).expiry_ts.is_some_and(|e| e <= now_ts) {
+                    if expired_dropped >= DROP_EXPIRED_ORDER_LIMIT {
+                        break;
+                    }
+                    let expired = self.bids.remove(handle);
+                    self.order_locations.remove(&expired.id);
+                    expired_dropped += 1;
+                    continue;
+                }
+
+                if self.bids.get(handle).owner == order.owner {
+                    match order.self_trade_behavior {
+                        SelfTradeBehavior::CancelProvide => {
+                            let cancelled = self.bids.remove(handle);
+                            self.order_locations.remove(&cancelled.id);
+                            continue;
+                        }
+                        SelfTradeBehavior::DecrementTake => {
+                            let overlap =
+                                std::cmp::min(order.quantity, self.bids.get(handle).quantity);
+                            order.quantity -= overlap;
+                            self.bids.get_mut(handle).quantity -= overlap;
+                            if self.bids.get(handle).quantity == 0 {
+                                let drained = self.bids.remove(handle);
+                                self.order_locations.remove(&drained.id);
+                            }
+                            continue;
+                        }
+                        SelfTradeBehavior::AbortTransaction => break,
+                    }
+                }
+
+                let maker_id = self.bids.get(handle).id;
+                let trade_qty = std::cmp::min(order.quantity, self.bids.get(handle).quantity);
+                order.quantity -= trade_qty;
+                self.bids.get_mut(handle).quantity -= trade_qty;
+
+                fills.push(FillEvent {
+                    taker_id: order.id,
+                    maker_id,
+                    price: best_bid_price,
+                    quantity: trade_qty,
+                });
+
+                if self.bids.get(handle).quantity == 0 {
+                    let filled = self.bids.remove(handle);
+                    self.order_locations.remove(&filled.id);
+                }
+            }
+        }
+
+        // 4. PARK (market orders are discarded instead of resting)
+        if order.quantity > 0 && order.kind == OrderKind::Limit {
+            match order.peg_offset {
+                Some(offset) => {
+                    self.order_locations
+                        .insert(order.id, OrderLocation::PeggedAsk(offset));
+                    self.pegged_asks
+                        .entry(offset)
+                        .or_default()
+                        .push_back(order.clone());
+                }
+                None => {
+                    let sequence = self.next_sequence;
+                    self.next_sequence += 1;
+                    let key = pack_ask_key(order.price, sequence);
+                    let handle = self.asks.insert(key, order.clone());
+                    self.order_locations
+                        .insert(order.id, OrderLocation::FixedAsk(handle));
+                }
+            }
+        }
+
+        fills
+    }
+
+    // --- 3. BOOK MANAGEMENT (Cancel / Amend) ---
+
+    /// Removes a resting order by id, dropping its pegged-level entry if it
+    /// becomes empty (fixed-price orders free their slab slot directly).
+    /// Returns whether an order with that id was found.
+    fn cancel_order(&mut self, id: u64) -> bool {
+        match self.order_locations.remove(&id) {
+            Some(OrderLocation::FixedBid(handle)) => {
+                self.bids.remove(handle);
+                true
+            }
+            Some(OrderLocation::FixedAsk(handle)) => {
+                self.asks.remove(handle);
+                true
+            }
+            Some(OrderLocation::PeggedBid(offset)) => {
+                Self::remove_from_pegged_side(&mut self.pegged_bids, offset, id)
+            }
+            Some(OrderLocation::PeggedAsk(offset)) => {
+                Self::remove_from_pegged_side(&mut self.pegged_asks, offset, id)
+            }
+            None => false,
+        }
+    }
+
+    /// Reduces a resting order's quantity in place. Increases are rejected
+    /// because growing an order should lose its place in time priority
+    /// (that requires cancel + re-add, not amend). A reduction to zero
+    /// removes the order entirely.
+    fn amend_order(&mut self, id: u64, new_quantity: u32) -> bool {
+        let location = match self.order_locations.get(&id) {
+            Some(&location) => location,
+            None => return false,
+        };
+
+        if new_quantity == 0 {
+            return self.cancel_order(id);
+        }
+
+        match location {
+            OrderLocation::FixedBid(handle) => Self::amend_handle(&mut self.bids, handle, new_quantity),
+            OrderLocation::FixedAsk(handle) => Self::amend_handle(&mut self.asks, handle, new_quantity),
+            OrderLocation::PeggedBid(offset) => {
+                Self::amend_in_pegged_side(&mut self.pegged_bids, offset, id, new_quantity)
+            }
+            OrderLocation::PeggedAsk(offset) => {
+                Self::amend_in_pegged_side(&mut self.pegged_asks, offset, id, new_quantity)
+            }
+        }
+    }
+
+    fn amend_handle(slab: &mut Slab, handle: u32, new_quantity: u32) -> bool {
+        let resting = slab.get_mut(handle);
+        if new_quantity <= resting.quantity {
+            resting.quantity = new_quantity;
+            true
+        } else {
+            false // reject increases
+        }
+    }
+
+    fn remove_from_pegged_side(
+        side: &mut BTreeMap<i64, VecDeque<Order>>,
+        offset: i64,
+        id: u64,
+    ) -> bool {
+        let level = match side.get_mut(&offset) {
+            Some(level) => level,
+            None => return false,
+        };
+
+        let found = match level.iter().position(|o| o.id == id) {
+            Some(idx) => {
+                level.remove(idx);
+                true
+            }
+            None => false,
+        };
+
+        if level.is_empty() {
+            side.remove(&offset);
+        }
+
+        found
+    }
+
+    fn amend_in_pegged_side(
+        side: &mut BTreeMap<i64, VecDeque<Order>>,
+        offset: i64,
+        id: u64,
+        new_quantity: u32,
+    ) -> bool {
+        let level = match side.get_mut(&offset) {
+            Some(level) => level,
+            None => return false,
+        };
+
+        match level.iter_mut().find(|o| o.id == id) {
+            Some(resting) if new_quantity <= resting.quantity => {
+                resting.quantity = new_quantity;
+                true
+            }
+            Some(_) => false, // reject increases
+            None => false,
+        }
+    }
+
+    // --- 4. MARKET DATA (Read API) ---
+
+    /// Highest resting bid, across both the fixed-price slab and the pegged
+    /// tree.
+    fn best_bid(&self) -> Option<u64> {
+        let fixed = self.bids.best().map(|h| self.bids.get(h).price);
+        let pegged = self
+            .pegged_bids
+            .keys()
+            .next_back()
+            .map(|&offset| self.effective_pegged_price(offset));
+
+        match (fixed, pegged) {
+            (None, None) => None,
+            (Some(f), None) => Some(f),
+            (None, Some(p)) => Some(p),
+            (Some(f), Some(p)) => Some(f.max(p)),
+        }
+    }
+
+    /// Lowest resting ask, across both the fixed-price slab and the pegged
+    /// tree.
+    fn best_ask(&self) -> Option<u64> {
+        let fixed = self.asks.best().map(|h| self.asks.get(h).price);
+        let pegged = self
+            .pegged_asks
+            .keys()
+            .next()
+            .map(|&offset| self.effective_pegged_price(offset));
+
+        match (fixed, pegged) {
+            (None, None) => None,
+            (Some(f), None) => Some(f),
+            (None, Some(p)) => Some(p),
+            (Some(f), Some(p)) => Some(f.min(p)),
+        }
+    }
+
+    /// Gap between the best ask and the best bid, or `None` if either side
+    /// of the book is empty.
+    fn spread(&self) -> Option<u64> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some(ask.saturating_sub(bid)),
+            _ => None,
+        }
+    }
+
+    /// Yields `(price, quantity)` for every resting unit on `side` -- one
+    /// fixed-price order, or one pegged price level -- in best-first order,
+    /// merging the fixed-price slab and the pegged tree lazily instead of
+    /// materializing either into a map first. Equal prices aren't collapsed
+    /// here (a price can appear once from the slab per resting order there,
+    /// plus once more from a pegged level); callers merge runs of equal
+    /// price themselves, which lets them stop as soon as they have what
+    /// they need instead of walking the whole side.
+    fn merged_best_first<'a>(&'a self, side: Side) -> Box<dyn Iterator<Item = (u64, u64)> + 'a> {
+        match side {
+            Side::Bid => {
+                let mut fixed = self.bids.iter_best_first().peekable();
+                let mut pegged = self.pegged_bids.iter().rev().peekable();
+                Box::new(std::iter::from_fn(move || {
+                    let fixed_price = fixed.peek().map(|o| o.price);
+                    let pegged_price = pegged
+                        .peek()
+                        .map(|&(&offset, _)| self.effective_pegged_price(offset));
+
+                    let take_fixed = match (fixed_price, pegged_price) {
+                        (None, None) => return None,
+                        (Some(_), None) => true,
+                        (None, Some(_)) => false,
+                        (Some(fp), Some(pp)) => fp >= pp,
+                    };
 
-                if best_bid.quantity == 0 {
-                    orders_at_level.pop_front();
+                    if take_fixed {
+                        fixed.next().map(|o| (o.price, o.quantity as u64))
+                    } else {
+                        pegged.next().map(|(&offset, level)| {
+                            (self.effective_pegged_price(offset), level_quantity(level))
+                        })
+                    }
+                }))
+            }
+            Side::Ask => {
+                let mut fixed = self.asks.iter_best_first().peekable();
+                let mut pegged = self.pegged_asks.iter().peekable();
+                Box::new(std::iter::from_fn(move || {
+                    let fixed_price = fixed.peek().map(|o| o.price);
+                    let pegged_price = pegged
+                        .peek()
+                        .map(|&(&offset, _)| self.effective_pegged_price(offset));
+
+                    let take_fixed = match (fixed_price, pegged_price) {
+                        (None, None) => return None,
+                        (Some(_), None) => true,
+                        (None, Some(_)) => false,
+                        (Some(fp), Some(pp)) => fp <= pp,
+                    };
+
+                    if take_fixed {
+                        fixed.next().map(|o| (o.price, o.quantity as u64))
+                    } else {
+                        pegged.next().map(|(&offset, level)| {
+                            (self.effective_pegged_price(offset), level_quantity(level))
+                        })
+                    }
+                }))
+            }
+        }
+    }
+
+    /// Total resting quantity per price level, from the top of the book
+    /// down, for at most `levels` price points. Stops walking the merged
+    /// stream as soon as `levels` distinct prices are collected, rather
+    /// than aggregating the whole side first.
+    fn depth(&self, side: Side, levels: usize) -> Vec<(u64, u64)> {
+        let mut result = Vec::with_capacity(levels);
+        if levels == 0 {
+            return result;
+        }
+        let mut current: Option<(u64, u64)> = None;
+
+        for (price, qty) in self.merged_best_first(side) {
+            match current {
+                Some((cur_price, cur_qty)) if cur_price == price => {
+                    current = Some((cur_price, cur_qty + qty));
+                }
+                Some(level) => {
+                    result.push(level);
+                    if result.len() == levels {
+                        return result;
+                    }
+                    current = Some((price, qty));
                 }
+                None => current = Some((price, qty)),
             }
+        }
 
-            if orders_at_level.is_empty() {
-                self.bids.remove(&best_bid_price);
+        if let Some(level) = current {
+            if result.len() < levels {
+                result.push(level);
             }
         }
 
-        // 4. PARK
-        if order.quantity > 0 {
-            self.asks
-                .entry(order.price)
-                .or_insert_with(VecDeque::new)
-                .push_back(order.clone());
+        result
+    }
+
+    /// Total resting size at a price that's at least as good as `price` for
+    /// a taker: bids at or above it, asks at or below it. The merged stream
+    /// is monotonic in the "better" direction, so this stops at the first
+    /// entry past the cutoff instead of summing the whole side.
+    fn quantity_at_or_better(&self, side: Side, price: u64) -> u64 {
+        let mut total = 0u64;
+        for (level_price, qty) in self.merged_best_first(side) {
+            let in_range = match side {
+                Side::Bid => level_price >= price,
+                Side::Ask => level_price <= price,
+            };
+            if !in_range {
+                break;
+            }
+            total += qty;
         }
+        total
     }
 }
 
-
+fn level_quantity(level: &VecDeque<Order>) -> u64 {
+    level.iter().map(|o| o.quantity as u64).sum()
+}
 
 fn main() {
-    let mut book = OrderBook::new();
+    let mut book = OrderBook::new(1, 1, 1);
     let mut rng = rand::thread_rng();
     let total_orders = 1_000_000;
 
     println!(" INITIALIZING HIGH-FREQUENCY ENGINE...");
     println!("Target: Process {} Orders", total_orders);
 
-  
+    // Owners are drawn from a small pool rather than being unique per order,
+    // so the self-trade modes below actually fire under load instead of
+    // just being assigned to orders that never collide with themselves.
+    let owner_pool = 1_000;
+    let self_trade_modes = [
+        SelfTradeBehavior::CancelProvide,
+        SelfTradeBehavior::DecrementTake,
+        SelfTradeBehavior::AbortTransaction,
+    ];
+
+    let mut oracle_price: u64 = 10_000;
+    book.set_oracle_price(oracle_price);
+
+    let mut total_fills = 0u64;
+    let mut self_trades_aborted = 0u64;
+    let mut cancels_applied = 0u64;
+    let mut amends_applied = 0u64;
+    let mut max_spread_seen = 0u64;
+
     let start_time = Instant::now();
 
     for i in 0..total_orders {
-      ]
+        let now_ts = i;
+
+        // Walk the oracle under load so pegged orders actually reprice
+        // against a moving reference, the same way a live feed would.
+        if i % 2_000 == 0 {
+            let drift: i64 = rng.gen_range(-25..=25);
+            oracle_price = (oracle_price as i64 + drift).max(0) as u64;
+            book.set_oracle_price(oracle_price);
+        }
+
         let is_buy = rng.gen_bool(0.5);
-        let price = rng.gen_range(9000..11000); 
-        let qty = rng.gen_range(1..100);
+        let is_pegged = rng.gen_bool(0.05);
+        let is_market = !is_pegged && rng.gen_bool(0.02);
+        let has_expiry = rng.gen_bool(0.05);
 
         let order = Order {
             id: i,
@@ -167,11 +1336,58 @@ fn main() {
             } else {
                 OrderType::Sell
             },
-            price,
-            quantity: qty,
+            kind: if is_market {
+                OrderKind::Market
+            } else {
+                OrderKind::Limit
+            },
+            owner: i % owner_pool,
+            self_trade_behavior: self_trade_modes[(i % self_trade_modes.len() as u64) as usize],
+            expiry_ts: if has_expiry {
+                Some(now_ts + rng.gen_range(1..50))
+            } else {
+                None
+            },
+            peg_offset: if is_pegged {
+                Some(rng.gen_range(-500..500))
+            } else {
+                None
+            },
+            price: if is_pegged { 0 } else { rng.gen_range(9000..11000) },
+            quantity: rng.gen_range(1..100),
         };
 
-        book.add_order(order);
+        match book.add_order(order, now_ts) {
+            Ok(fills) => total_fills += fills.len() as u64,
+            Err(OrderError::SelfTrade) => self_trades_aborted += 1,
+            Err(other) => panic!("benchmark orders are always on-grid, got {:?}", other),
+        }
+
+        // Periodically cancel or amend a recently-placed order and sample
+        // the read API, so the same load this benchmark already drives
+        // exercises cancel/amend/market-data rather than only add_order.
+        if i % 1_000 == 999 {
+            let recent = i - rng.gen_range(0..1_000);
+            if rng.gen_bool(0.5) {
+                if book.cancel_order(recent) {
+                    cancels_applied += 1;
+                }
+            } else {
+                let new_quantity = rng.gen_range(0..50);
+                if book.amend_order(recent, new_quantity) {
+                    amends_applied += 1;
+                }
+            }
+
+            if let Some(spread) = book.spread() {
+                max_spread_seen = max_spread_seen.max(spread);
+            }
+            let _bid_depth = book.depth(Side::Bid, 5);
+            let _ask_depth = book.depth(Side::Ask, 5);
+            if let Some(best_bid) = book.best_bid() {
+                let _ = book.quantity_at_or_better(Side::Bid, best_bid);
+            }
+        }
     }
 
     let duration = start_time.elapsed();
@@ -186,5 +1402,441 @@ fn main() {
     println!("Time Taken:      {:.4} seconds", seconds);
     println!("Throughput:      {:.0} Orders/Sec", ops);
     println!("Latency per Order: {:.0} nanoseconds", latency_ns);
+    println!("Fills:           {}", total_fills);
+    println!("Self-trades aborted: {}", self_trades_aborted);
+    println!("Cancels applied: {}", cancels_applied);
+    println!("Amends applied:  {}", amends_applied);
+    println!("Max spread seen: {}", max_spread_seen);
     println!("---------------------------------------------");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_order(id: u64, order_type: OrderType, owner: u64, price: u64, quantity: u32) -> Order {
+        Order {
+            id,
+            order_type,
+            kind: OrderKind::Limit,
+            owner,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            expiry_ts: None,
+            peg_offset: None,
+            price,
+            quantity,
+        }
+    }
+
+    #[test]
+    fn fifo_time_priority_within_a_price_level() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 5), 0)
+            .unwrap();
+        book.add_order(make_order(2, OrderType::Sell, 11, 100, 5), 0)
+            .unwrap();
+
+        let fills = book
+            .add_order(make_order(3, OrderType::Buy, 20, 100, 5), 0)
+            .unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].maker_id, 1, "earlier resting order at the same price must fill first");
+        assert_eq!(fills[0].taker_id, 3);
+        assert_eq!(fills[0].quantity, 5);
+    }
+
+    #[test]
+    fn fill_executes_at_the_maker_price_not_the_taker_price() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 95, 5), 0)
+            .unwrap();
+
+        let fills = book
+            .add_order(make_order(2, OrderType::Buy, 20, 100, 5), 0)
+            .unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 95, "trade must execute at the resting maker's price");
+    }
+
+    #[test]
+    fn cancel_order_removes_it_from_the_book() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 5), 0)
+            .unwrap();
+
+        assert!(book.cancel_order(1));
+        assert!(!book.cancel_order(1), "cancelling twice must not succeed twice");
+
+        let fills = book
+            .add_order(make_order(2, OrderType::Buy, 20, 100, 5), 0)
+            .unwrap();
+        assert!(fills.is_empty(), "a cancelled order must not be matched against");
+    }
+
+    #[test]
+    fn amend_order_can_only_reduce_quantity() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 10), 0)
+            .unwrap();
+
+        assert!(book.amend_order(1, 4));
+        assert!(!book.amend_order(1, 9), "amend must reject quantity increases");
+
+        let fills = book
+            .add_order(make_order(2, OrderType::Buy, 20, 100, 10), 0)
+            .unwrap();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].quantity, 4, "resting order should only have its amended quantity left");
+    }
+
+    #[test]
+    fn amend_to_zero_cancels_the_order() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 5), 0)
+            .unwrap();
+
+        assert!(book.amend_order(1, 0));
+
+        let fills = book
+            .add_order(make_order(2, OrderType::Buy, 20, 100, 5), 0)
+            .unwrap();
+        assert!(fills.is_empty());
+    }
+
+    #[test]
+    fn market_order_sweeps_available_liquidity() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 5), 0)
+            .unwrap();
+
+        let order = Order {
+            kind: OrderKind::Market,
+            ..make_order(2, OrderType::Buy, 20, 0, 5)
+        };
+        let fills = book.add_order(order, 0).unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 100);
+        assert_eq!(fills[0].quantity, 5);
+    }
+
+    #[test]
+    fn unfilled_market_order_remainder_is_discarded_not_parked() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 10, 100, 3), 0)
+            .unwrap();
+
+        let order = Order {
+            kind: OrderKind::Market,
+            ..make_order(2, OrderType::Buy, 20, 0, 10)
+        };
+        let fills = book.add_order(order, 0).unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].quantity, 3);
+        assert_eq!(book.best_bid(), None, "unfilled market order quantity must not rest on the book");
+    }
+
+    #[test]
+    fn off_tick_price_is_rejected() {
+        let mut book = OrderBook::new(5, 1, 1);
+        let result = book.add_order(make_order(1, OrderType::Buy, 1, 101, 5), 0);
+        assert_eq!(result, Err(OrderError::InvalidTick));
+    }
+
+    #[test]
+    fn off_lot_quantity_is_rejected() {
+        let mut book = OrderBook::new(1, 5, 1);
+        let result = book.add_order(make_order(1, OrderType::Buy, 1, 100, 7), 0);
+        assert_eq!(result, Err(OrderError::InvalidLot));
+    }
+
+    #[test]
+    fn below_minimum_quantity_is_rejected() {
+        let mut book = OrderBook::new(1, 1, 10);
+        let result = book.add_order(make_order(1, OrderType::Buy, 1, 100, 5), 0);
+        assert_eq!(result, Err(OrderError::BelowMinimum));
+    }
+
+    #[test]
+    fn zero_tick_size_rejects_every_nonzero_price_instead_of_panicking() {
+        let mut book = OrderBook::new(0, 1, 1);
+        let result = book.add_order(make_order(1, OrderType::Buy, 1, 100, 5), 0);
+        assert_eq!(result, Err(OrderError::InvalidTick));
+    }
+
+    #[test]
+    fn zero_lot_size_rejects_every_nonzero_quantity_instead_of_panicking() {
+        let mut book = OrderBook::new(1, 0, 1);
+        let result = book.add_order(make_order(1, OrderType::Buy, 1, 100, 5), 0);
+        assert_eq!(result, Err(OrderError::InvalidLot));
+    }
+
+    #[test]
+    fn self_trade_cancel_provide_cancels_the_resting_order_and_keeps_matching() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 42, 100, 5), 0)
+            .unwrap();
+        book.add_order(make_order(2, OrderType::Sell, 99, 100, 5), 0)
+            .unwrap();
+
+        let order = Order {
+            self_trade_behavior: SelfTradeBehavior::CancelProvide,
+            ..make_order(3, OrderType::Buy, 42, 100, 5)
+        };
+        let fills = book.add_order(order, 0).unwrap();
+
+        assert_eq!(fills.len(), 1, "the same-owner resting order is cancelled, not filled");
+        assert_eq!(fills[0].maker_id, 2, "matching continues against the next, non-self, resting order");
+    }
+
+    #[test]
+    fn self_trade_decrement_take_reduces_both_sides_without_a_fill() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 42, 100, 5), 0)
+            .unwrap();
+
+        let order = Order {
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            ..make_order(2, OrderType::Buy, 42, 100, 3)
+        };
+        let fills = book.add_order(order, 0).unwrap();
+
+        assert!(fills.is_empty(), "decrement-take resolves a self-trade without emitting a fill");
+        assert_eq!(book.best_ask(), Some(100));
+        assert_eq!(book.depth(Side::Ask, 1), vec![(100, 2)], "resting order should be left with 5 - 3 = 2");
+    }
+
+    #[test]
+    fn self_trade_abort_transaction_rejects_and_leaves_the_book_untouched() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 42, 100, 5), 0)
+            .unwrap();
+
+        let order = Order {
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            ..make_order(2, OrderType::Buy, 42, 100, 5)
+        };
+        let result = book.add_order(order, 0);
+
+        assert_eq!(result, Err(OrderError::SelfTrade));
+        assert_eq!(book.best_ask(), Some(100), "the resting order must be untouched after the abort");
+        assert_eq!(book.depth(Side::Ask, 1), vec![(100, 5)]);
+    }
+
+    #[test]
+    fn expired_resting_order_is_evicted_and_skipped() {
+        let mut book = OrderBook::new(1, 1, 1);
+        let expired = Order {
+            expiry_ts: Some(100),
+            ..make_order(1, OrderType::Sell, 10, 100, 5)
+        };
+        book.add_order(expired, 0).unwrap();
+        book.add_order(make_order(2, OrderType::Sell, 11, 100, 5), 0)
+            .unwrap();
+
+        let fills = book
+            .add_order(make_order(3, OrderType::Buy, 20, 100, 5), 200)
+            .unwrap();
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].maker_id, 2, "the expired order must be evicted instead of filled");
+        assert!(!book.cancel_order(1), "the expired order should already be gone from the book");
+    }
+
+    #[test]
+    fn expired_order_eviction_is_bounded_per_call() {
+        let mut book = OrderBook::new(1, 1, 1);
+        for i in 0..(DROP_EXPIRED_ORDER_LIMIT as u64 + 3) {
+            let expired = Order {
+                expiry_ts: Some(100),
+                ..make_order(i, OrderType::Sell, 10 + i, 100, 1)
+            };
+            book.add_order(expired, 0).unwrap();
+        }
+
+        let fills = book
+            .add_order(make_order(9000, OrderType::Buy, 99, 100, 1), 200)
+            .unwrap();
+
+        assert!(
+            fills.is_empty(),
+            "a taker should stop matching once the per-call expired-eviction limit is hit"
+        );
+        assert_eq!(
+            book.depth(Side::Ask, 1),
+            vec![(100, 3)],
+            "orders past the eviction limit must remain on the book for a later call"
+        );
+    }
+
+    #[test]
+    fn abort_transaction_preflight_is_bounded_like_real_matching() {
+        // Stack DROP_EXPIRED_ORDER_LIMIT + 1 expired orders in front of a
+        // genuine self-trade. Real matching would give up evicting before
+        // ever reaching the self-trade, so the AbortTransaction preflight
+        // must agree and let the order through rather than rejecting it.
+        let mut book = OrderBook::new(1, 1, 1);
+        for i in 0..(DROP_EXPIRED_ORDER_LIMIT as u64 + 1) {
+            let expired = Order {
+                expiry_ts: Some(100),
+                ..make_order(i, OrderType::Sell, 10 + i, 100, 1)
+            };
+            book.add_order(expired, 0).unwrap();
+        }
+        book.add_order(make_order(9001, OrderType::Sell, 42, 100, 1), 0)
+            .unwrap();
+
+        let order = Order {
+            self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+            ..make_order(9002, OrderType::Buy, 42, 100, 1)
+        };
+        let result = book.add_order(order, 200);
+
+        assert!(
+            result.is_ok(),
+            "the same-owner resting order sits past the eviction limit, so real matching would never reach it"
+        );
+    }
+
+    #[test]
+    fn pegged_order_reprices_when_the_oracle_moves() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.set_oracle_price(1000);
+        let pegged = Order {
+            peg_offset: Some(-5),
+            ..make_order(1, OrderType::Sell, 10, 0, 5)
+        };
+        book.add_order(pegged, 0).unwrap();
+        assert_eq!(book.best_ask(), Some(995));
+
+        book.set_oracle_price(1010);
+        assert_eq!(
+            book.best_ask(),
+            Some(1005),
+            "a pegged order's effective price must track oracle updates without being re-added"
+        );
+    }
+
+    #[test]
+    fn cancel_and_amend_reach_pegged_orders_including_one_sharing_its_peg_level() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.set_oracle_price(1000);
+
+        let first = Order {
+            peg_offset: Some(-5),
+            ..make_order(1, OrderType::Sell, 10, 0, 5)
+        };
+        let second = Order {
+            peg_offset: Some(-5),
+            ..make_order(2, OrderType::Sell, 11, 0, 3)
+        };
+        book.add_order(first, 0).unwrap();
+        book.add_order(second, 0).unwrap();
+
+        assert!(
+            book.amend_order(1, 2),
+            "amend must reach into a pegged level by id"
+        );
+        assert_eq!(
+            book.quantity_at_or_better(Side::Ask, 995),
+            5,
+            "amending one order on a shared peg level must leave the other (2 + 3)"
+        );
+
+        assert!(book.cancel_order(1));
+        assert_eq!(
+            book.quantity_at_or_better(Side::Ask, 995),
+            3,
+            "cancelling one order on a shared peg level must leave the other resting"
+        );
+        assert_eq!(
+            book.best_ask(),
+            Some(995),
+            "the peg level survives as long as one order remains on it"
+        );
+
+        assert!(book.cancel_order(2));
+        assert_eq!(
+            book.best_ask(),
+            None,
+            "the peg level itself must be dropped once its last order is cancelled"
+        );
+        assert!(!book.cancel_order(2), "cancelling twice must not succeed twice");
+    }
+
+    #[test]
+    fn pegged_price_is_clamped_at_zero() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.set_oracle_price(3);
+        let pegged = Order {
+            peg_offset: Some(-10),
+            ..make_order(1, OrderType::Sell, 10, 0, 5)
+        };
+        book.add_order(pegged, 0).unwrap();
+        assert_eq!(book.best_ask(), Some(0));
+    }
+
+    #[test]
+    fn effective_pegged_price_saturates_instead_of_overflowing() {
+        let book_high = OrderBook {
+            oracle_price: u64::MAX,
+            ..OrderBook::new(1, 1, 1)
+        };
+        assert_eq!(book_high.effective_pegged_price(i64::MAX), u64::MAX);
+
+        let book_low = OrderBook::new(1, 1, 1);
+        assert_eq!(book_low.effective_pegged_price(i64::MIN), 0);
+    }
+
+    #[test]
+    fn best_bid_ask_and_spread() {
+        let mut book = OrderBook::new(1, 1, 1);
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.spread(), None);
+
+        book.add_order(make_order(1, OrderType::Buy, 1, 95, 5), 0)
+            .unwrap();
+        book.add_order(make_order(2, OrderType::Sell, 2, 105, 5), 0)
+            .unwrap();
+
+        assert_eq!(book.best_bid(), Some(95));
+        assert_eq!(book.best_ask(), Some(105));
+        assert_eq!(book.spread(), Some(10));
+    }
+
+    #[test]
+    fn depth_returns_top_n_price_levels_best_first() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Buy, 1, 90, 5), 0)
+            .unwrap();
+        book.add_order(make_order(2, OrderType::Buy, 2, 95, 3), 0)
+            .unwrap();
+        book.add_order(make_order(3, OrderType::Buy, 3, 95, 2), 0)
+            .unwrap();
+        book.add_order(make_order(4, OrderType::Buy, 4, 80, 1), 0)
+            .unwrap();
+
+        assert_eq!(
+            book.depth(Side::Bid, 2),
+            vec![(95, 5), (90, 5)],
+            "bids must be ordered highest price first, with same-price orders merged into one level"
+        );
+    }
+
+    #[test]
+    fn quantity_at_or_better_sums_only_the_qualifying_levels() {
+        let mut book = OrderBook::new(1, 1, 1);
+        book.add_order(make_order(1, OrderType::Sell, 1, 100, 5), 0)
+            .unwrap();
+        book.add_order(make_order(2, OrderType::Sell, 2, 101, 5), 0)
+            .unwrap();
+        book.add_order(make_order(3, OrderType::Sell, 3, 102, 5), 0)
+            .unwrap();
+
+        assert_eq!(book.quantity_at_or_better(Side::Ask, 101), 10);
+    }
+}